@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Result};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::env;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Zlib,
+    Brotli,
+    Zstd,
+}
+
+impl Codec {
+    fn from_name(name: &str) -> Result<Codec> {
+        match name.to_lowercase().as_str() {
+            "gzip" | "gz" => Ok(Codec::Gzip),
+            "zlib" | "deflate" => Ok(Codec::Zlib),
+            "brotli" | "br" => Ok(Codec::Brotli),
+            "zstd" | "zst" => Ok(Codec::Zstd),
+            other => Err(anyhow!("unknown compression codec: {other}")),
+        }
+    }
+
+    pub fn content_encoding(&self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Zlib => "deflate",
+            Codec::Brotli => "br",
+            Codec::Zstd => "zstd",
+        }
+    }
+}
+
+/// Selects the codec/level to compress bulk bodies with, read from
+/// `BULK_COMPRESSION_CODEC` (default `gzip`) and `BULK_COMPRESSION_LEVEL`
+/// (default `6`).
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionConfig {
+    pub codec: Codec,
+    pub level: u32,
+}
+
+impl CompressionConfig {
+    pub fn from_env() -> Result<CompressionConfig> {
+        let codec = match env::var("BULK_COMPRESSION_CODEC") {
+            Ok(name) => Codec::from_name(&name)?,
+            Err(_) => Codec::Gzip,
+        };
+
+        let level = env::var("BULK_COMPRESSION_LEVEL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(6);
+
+        Ok(CompressionConfig { codec, level })
+    }
+}
+
+/// Streams `chunks` through the configured codec's incremental encoder so a
+/// large bulk body never needs to be buffered uncompressed in full.
+pub fn compress_stream<'a>(config: CompressionConfig, chunks: impl Iterator<Item = &'a [u8]>) -> Result<Vec<u8>> {
+    match config.codec {
+        Codec::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::new(config.level));
+            for chunk in chunks {
+                encoder.write_all(chunk)?;
+            }
+            Ok(encoder.finish()?)
+        }
+        Codec::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(config.level));
+            for chunk in chunks {
+                encoder.write_all(chunk)?;
+            }
+            Ok(encoder.finish()?)
+        }
+        Codec::Brotli => {
+            let quality = config.level.min(11);
+            let mut out = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, quality, 22);
+                for chunk in chunks {
+                    encoder.write_all(chunk)?;
+                }
+                encoder.flush()?;
+            }
+            Ok(out)
+        }
+        Codec::Zstd => {
+            let mut encoder = zstd::stream::Encoder::new(Vec::new(), config.level as i32)?;
+            for chunk in chunks {
+                encoder.write_all(chunk)?;
+            }
+            Ok(encoder.finish()?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn codec_from_name_accepts_known_aliases() {
+        assert_eq!(Codec::from_name("gz").unwrap(), Codec::Gzip);
+        assert_eq!(Codec::from_name("DEFLATE").unwrap(), Codec::Zlib);
+        assert_eq!(Codec::from_name("br").unwrap(), Codec::Brotli);
+        assert_eq!(Codec::from_name("zst").unwrap(), Codec::Zstd);
+    }
+
+    #[test]
+    fn codec_from_name_rejects_unknown_names() {
+        assert!(Codec::from_name("lz4").is_err());
+    }
+
+    #[test]
+    fn content_encoding_matches_the_http_header_values() {
+        assert_eq!(Codec::Gzip.content_encoding(), "gzip");
+        assert_eq!(Codec::Zlib.content_encoding(), "deflate");
+        assert_eq!(Codec::Brotli.content_encoding(), "br");
+        assert_eq!(Codec::Zstd.content_encoding(), "zstd");
+    }
+
+    #[test]
+    fn compress_stream_gzip_round_trips() {
+        let config = CompressionConfig { codec: Codec::Gzip, level: 6 };
+        let compressed = compress_stream(config, [b"hello ".as_slice(), b"world".as_slice()].into_iter()).unwrap();
+
+        let mut decoder = flate2::read::GzDecoder::new(compressed.as_slice());
+        let mut decompressed = String::new();
+        decoder.read_to_string(&mut decompressed).unwrap();
+
+        assert_eq!(decompressed, "hello world");
+    }
+}
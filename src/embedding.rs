@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+
+/// Splits long text into overlap-free chunks so the embedding model's input
+/// limit isn't exceeded. Chunking is word-based rather than byte-based so we
+/// don't cut a token in half.
+pub struct Splitter {
+    pub chunk_size: usize,
+}
+
+impl Splitter {
+    pub fn new(chunk_size: usize) -> Self {
+        Splitter { chunk_size }
+    }
+
+    pub fn split(&self, text: &str) -> Vec<String> {
+        if text.is_empty() {
+            return Vec::new();
+        }
+
+        text.split_whitespace()
+            .collect::<Vec<_>>()
+            .chunks(self.chunk_size.max(1))
+            .map(|words| words.join(" "))
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingDatum>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingDatum {
+    embedding: Vec<f32>,
+}
+
+/// A document -> chunk -> embedding pipeline for a single description field.
+/// `field` is informational only (it documents which field this pipeline was
+/// built for); callers pass the text in directly.
+pub struct Pipeline {
+    pub field: String,
+    pub model: String,
+    pub endpoint: String,
+    splitter: Splitter,
+}
+
+impl Pipeline {
+    pub fn new(field: impl Into<String>, model: impl Into<String>, endpoint: impl Into<String>, chunk_size: usize) -> Self {
+        Pipeline {
+            field: field.into(),
+            model: model.into(),
+            endpoint: endpoint.into(),
+            splitter: Splitter::new(chunk_size),
+        }
+    }
+
+    /// Embeds `texts` (one document per entry) by chunking each, calling the
+    /// embedding endpoint in a single batched request per document, and
+    /// averaging chunk embeddings back down to one vector per document.
+    /// Documents are processed in parallel via rayon. A `None` entry means
+    /// the document had nothing to embed (an empty description), not a
+    /// zero-dimensional vector.
+    pub fn embed_all(&self, texts: &[String]) -> Result<Vec<Option<Vec<f32>>>> {
+        let client = Client::new();
+
+        texts
+            .par_iter()
+            .map(|text| self.embed_one(&client, text))
+            .collect()
+    }
+
+    fn embed_one(&self, client: &Client, text: &str) -> Result<Option<Vec<f32>>> {
+        let chunks = self.splitter.split(text);
+        if chunks.is_empty() {
+            return Ok(None);
+        }
+
+        let request = EmbeddingRequest {
+            model: &self.model,
+            input: &chunks,
+        };
+
+        let response: EmbeddingResponse = client
+            .post(&self.endpoint)
+            .json(&request)
+            .send()?
+            .error_for_status()?
+            .json()?;
+
+        if response.data.is_empty() {
+            return Err(anyhow!("embedding endpoint returned no vectors for {} chunks", chunks.len()));
+        }
+
+        Ok(Some(average(response.data.into_iter().map(|d| d.embedding).collect())))
+    }
+}
+
+fn average(vectors: Vec<Vec<f32>>) -> Vec<f32> {
+    let dims = vectors[0].len();
+    let mut sum = vec![0f32; dims];
+
+    for vector in &vectors {
+        for (i, value) in vector.iter().enumerate() {
+            sum[i] += value;
+        }
+    }
+
+    let count = vectors.len() as f32;
+    sum.into_iter().map(|v| v / count).collect()
+}
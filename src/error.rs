@@ -0,0 +1,90 @@
+use std::fmt;
+
+/// Whether an `IndexerError` should abort the run or just get logged and
+/// skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Fatal,
+    Warning,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum IndexerError {
+    #[error("database unavailable: {0}")]
+    DatabaseUnavailable(String),
+
+    #[error("failed to fetch from {table}: {source}")]
+    FetchFailed {
+        table: &'static str,
+        #[source]
+        source: sqlx::Error,
+    },
+
+    #[error("elasticsearch unreachable: {0}")]
+    ElasticsearchUnreachable(String),
+
+    #[error("bulk insert reported {} failed document(s)", failed_ids.len())]
+    BulkPartialFailure { failed_ids: Vec<String> },
+}
+
+impl IndexerError {
+    /// A stable string code operators can grep for/alert on, independent of
+    /// the human-readable `Display` message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            IndexerError::DatabaseUnavailable(_) => "database_unavailable",
+            IndexerError::FetchFailed { .. } => "fetch_failed",
+            IndexerError::ElasticsearchUnreachable(_) => "elasticsearch_unreachable",
+            IndexerError::BulkPartialFailure { .. } => "bulk_partial_failure",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            IndexerError::BulkPartialFailure { .. } => Severity::Warning,
+            _ => Severity::Fatal,
+        }
+    }
+
+    /// Process exit code to use when this error reaches `main` and is fatal.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            IndexerError::DatabaseUnavailable(_) => 2,
+            IndexerError::FetchFailed { .. } => 3,
+            IndexerError::ElasticsearchUnreachable(_) => 4,
+            IndexerError::BulkPartialFailure { .. } => 0,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Fatal => write!(f, "fatal"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulk_partial_failure_is_a_warning_with_a_zero_exit_code() {
+        let error = IndexerError::BulkPartialFailure { failed_ids: vec!["1".to_string()] };
+
+        assert_eq!(error.severity(), Severity::Warning);
+        assert_eq!(error.exit_code(), 0);
+        assert_eq!(error.code(), "bulk_partial_failure");
+    }
+
+    #[test]
+    fn other_variants_are_fatal_with_a_nonzero_exit_code() {
+        let error = IndexerError::ElasticsearchUnreachable("connection refused".to_string());
+
+        assert_eq!(error.severity(), Severity::Fatal);
+        assert_eq!(error.code(), "elasticsearch_unreachable");
+        assert_ne!(error.exit_code(), 0);
+    }
+}
@@ -0,0 +1,107 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::Value;
+use sqlx::PgPool;
+
+#[derive(Debug, sqlx::Type, PartialEq, Eq, Clone, Copy)]
+#[sqlx(type_name = "job_status", rename_all = "lowercase")]
+pub enum JobStatus {
+    New,
+    Running,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: i64,
+    pub queue: String,
+    pub status: JobStatus,
+    pub payload: Value,
+    pub created_at: DateTime<Utc>,
+    pub heartbeat: Option<DateTime<Utc>>,
+}
+
+pub async fn enqueue(pool: &PgPool, queue: &str, payload: Value) -> Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO job_queue (queue, status, payload)
+VALUES ($1, 'new', $2)
+        "#,
+        queue,
+        payload,
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Claims up to `limit` unclaimed jobs from `queue`, marking them `running` so
+/// concurrent workers don't pick up the same row (`SKIP LOCKED`).
+pub async fn claim(pool: &PgPool, queue: &str, limit: i64) -> Result<Vec<Job>> {
+    let recs = sqlx::query!(
+        r#"
+UPDATE job_queue
+SET status = 'running', heartbeat = now()
+WHERE id IN (
+    SELECT id
+    FROM job_queue
+    WHERE queue = $1 AND status = 'new'
+    ORDER BY created_at
+    LIMIT $2
+    FOR UPDATE SKIP LOCKED
+)
+RETURNING id, queue, status as "status: JobStatus", payload, created_at, heartbeat
+        "#,
+        queue,
+        limit,
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(recs
+        .into_iter()
+        .map(|rec| Job {
+            id: rec.id,
+            queue: rec.queue,
+            status: rec.status,
+            payload: rec.payload,
+            created_at: rec.created_at,
+            heartbeat: rec.heartbeat,
+        })
+        .collect())
+}
+
+pub async fn complete(pool: &PgPool, job_id: i64) -> Result<()> {
+    sqlx::query!(
+        r#"
+UPDATE job_queue SET status = 'done', heartbeat = now() WHERE id = $1
+        "#,
+        job_id,
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Resets jobs stuck in `running` whose `heartbeat` is older than
+/// `stale_after` back to `new`, so a worker that claimed a job and then died
+/// (or never called `complete`) doesn't strand it there forever. Call this
+/// before `claim` on each run. Returns the number of jobs reclaimed.
+pub async fn reclaim_stale(pool: &PgPool, queue: &str, stale_after: Duration) -> Result<u64> {
+    let cutoff = Utc::now() - stale_after;
+    let result = sqlx::query!(
+        r#"
+UPDATE job_queue
+SET status = 'new', heartbeat = NULL
+WHERE queue = $1 AND status = 'running' AND heartbeat < $2
+        "#,
+        queue,
+        cutoff,
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
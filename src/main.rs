@@ -1,20 +1,37 @@
+mod compression;
+mod embedding;
+mod error;
+mod job_queue;
+mod pipeline;
+mod query;
+mod sink;
+mod watermark;
+
+use error::IndexerError;
+
 use dotenv::dotenv;
-use sqlx::{PgPool};
+use sqlx::{PgPool, Row};
 use std::{env};
 use anyhow::Result;
-use elasticsearch::{Elasticsearch, BulkParts};
-use serde_json::{json, Value};
+use chrono::{DateTime, Utc};
+use elasticsearch::Elasticsearch;
+use serde_json::json;
 use serde::{Serialize};
-use elasticsearch::http::request::JsonBody;
 use std::time::Instant;
-use rayon::prelude::*;
-use std::collections::HashMap;
+use std::sync::Arc;
+use std::collections::HashSet;
+
+const REINDEX_QUEUE: &str = "customer_reindex";
+const CLAIM_BATCH_SIZE: i64 = 500;
+const JOB_STALE_AFTER_MINUTES: i64 = 30;
 
 #[derive(Debug, Serialize, Clone)]
 pub struct Customer<> {
     pub customer_id: i64,
     pub description: String,
     pub orders: Vec<Order>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description_embedding: Option<Vec<f32>>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -23,6 +40,8 @@ pub struct Order<> {
     pub description: String,
     pub customer_id: i64,
     pub items: Vec<Item>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description_embedding: Option<Vec<f32>>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -30,6 +49,8 @@ pub struct Item {
     pub item_id: i64,
     pub description: String,
     pub order_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description_embedding: Option<Vec<f32>>,
 }
 
 #[tokio::main]
@@ -38,167 +59,382 @@ async fn main() -> Result<()> {
     dotenv().ok();
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set in .env file");
-    let pool = PgPool::new(&database_url).await?;
-
-    let custs = fetch_all_customers(&pool).await;
-    let customer_list = match custs {
-        Ok(custs) => custs,
-        _ => Vec::<Customer>::new()
-    };
-
-    let ords = fetch_all_orders(&pool).await;
-    let order_list = match ords {
-        Ok(ords) => ords,
-        _ => Vec::<Order>::new()
-    };
-
-    let itms = fetch_all_items(&pool).await;
-    let items_list = match itms {
-        Ok(itms) => itms,
-        _ => Vec::<Item>::new()
-    };
-
-    println!("fetched all data after {} milli_sec", now.elapsed().as_millis());
-
-    let mut items_map: HashMap<i64,Vec<Item>> = HashMap::new();
-    for item in &items_list {
-        items_map.entry(item.order_id).or_insert(Vec::new()).push(item.clone());
+    let pool = PgPool::new(&database_url)
+        .await
+        .map_err(|e| IndexerError::DatabaseUnavailable(e.to_string()))
+        .unwrap_or_else(|e| abort(e));
+
+    let since_customers = watermark::get(&pool, "customer").await?;
+    let since_orders = watermark::get(&pool, "order").await?;
+    let since_items = watermark::get(&pool, "item").await?;
+
+    let (changed_customers, max_customer_ts) = fetch_changed_customers(&pool, since_customers)
+        .await
+        .unwrap_or_else(|e| abort(e));
+    let (changed_orders, max_order_ts) = fetch_changed_orders(&pool, since_orders)
+        .await
+        .unwrap_or_else(|e| abort(e));
+    let (changed_items, max_item_ts) = fetch_changed_items(&pool, since_items)
+        .await
+        .unwrap_or_else(|e| abort(e));
+
+    println!("fetched delta after {} milli_sec", now.elapsed().as_millis());
+
+    let mut affected_customer_ids: HashSet<i64> = HashSet::new();
+    affected_customer_ids.extend(changed_customers.iter().map(|c| c.customer_id));
+    affected_customer_ids.extend(changed_orders.iter().map(|o| o.customer_id));
+
+    if !changed_items.is_empty() {
+        let order_ids: Vec<i64> = changed_items.iter().map(|i| i.order_id).collect();
+        let ids = customer_ids_for_order_ids(&pool, &order_ids).await.unwrap_or_else(|e| abort(e));
+        affected_customer_ids.extend(ids);
+    }
+
+    for customer_id in &affected_customer_ids {
+        job_queue::enqueue(&pool, REINDEX_QUEUE, json!({ "customer_id": customer_id })).await?;
     }
 
-    let orders: Vec<Order> = order_list.par_iter().map(|order| sort_data_orders(order.clone(), &items_map)).collect();
-    println!("converted orders after {} milli_sec", now.elapsed().as_millis());
+    println!("enqueued {} reindex jobs after {} milli_sec", affected_customer_ids.len(), now.elapsed().as_millis());
+
+    let client = Elasticsearch::default();
+    let embedding_pipeline = embedding_pipeline_from_env();
 
-    let mut orders_map: HashMap<i64,Vec<Order>> = HashMap::new();
-    for order in &orders {
-        orders_map.entry(order.customer_id).or_insert(Vec::new()).push(order.clone());
+    if sink::is_elasticsearch_sink() {
+        println!("{:?}", client.ping());
+        if let Some(pipeline) = &embedding_pipeline {
+            ensure_customer_mapping(&client, pipeline).await?;
+        }
     }
 
-    let customers: Vec<Customer> = customer_list.par_iter().map(|x| sort_data_customers(x.clone(), &orders_map)).collect();
-    println!("converted customers after {} sec", now.elapsed().as_millis());
+    let sink: Arc<dyn sink::DocumentSink> = Arc::from(sink::from_config(client).unwrap_or_else(|e| abort(e)));
+    let embedding_pipeline = embedding_pipeline.map(Arc::new);
 
-    println!("sorted all data after {} milli_sec", now.elapsed().as_millis());
+    let reclaimed = job_queue::reclaim_stale(&pool, REINDEX_QUEUE, chrono::Duration::minutes(JOB_STALE_AFTER_MINUTES)).await?;
+    if reclaimed > 0 {
+        println!("reclaimed {reclaimed} stale job(s) left running by a previous crashed run");
+    }
 
-    let client = Elasticsearch::default();
+    let mut all_jobs = Vec::new();
+    loop {
+        let jobs = job_queue::claim(&pool, REINDEX_QUEUE, CLAIM_BATCH_SIZE).await?;
+        if jobs.is_empty() {
+            break;
+        }
+        all_jobs.extend(jobs);
+    }
 
-    println!("{:?}", client.ping());
+    let customer_ids: Vec<i64> = all_jobs.iter().filter_map(|job| job.payload["customer_id"].as_i64()).collect();
+
+    // `failed_customer_ids` are the customers whose documents a sink rejected
+    // (see `IndexerError::BulkPartialFailure`); their jobs are left `running`
+    // below so `job_queue::reclaim_stale` retries them on a later run instead
+    // of us marking them `done` and advancing past their source rows.
+    let failed_customer_ids: HashSet<i64> = pipeline::run(&pool, customer_ids, sink, embedding_pipeline)
+        .await
+        .unwrap_or_else(|e| abort(e))
+        .into_iter()
+        .collect();
+
+    if !failed_customer_ids.is_empty() {
+        eprintln!(
+            "[bulk_partial_failure] {} customer(s) failed to index and will be retried on a later run",
+            failed_customer_ids.len()
+        );
+    }
 
-    bulk_insert_into_el(&client, customers, 2000).await?;
+    for job in &all_jobs {
+        let succeeded = match job.payload["customer_id"].as_i64() {
+            Some(customer_id) => !failed_customer_ids.contains(&customer_id),
+            None => true,
+        };
+        if succeeded {
+            job_queue::complete(&pool, job.id).await?;
+        }
+    }
+
+    // The latest `updated_at` we saw may belong to a customer that failed to
+    // index; only advance the watermarks once every job succeeded, so a
+    // partial failure doesn't hide those rows from the next run's delta scan.
+    if failed_customer_ids.is_empty() {
+        if let Some(ts) = max_customer_ts {
+            watermark::set(&pool, "customer", ts).await?;
+        }
+        if let Some(ts) = max_order_ts {
+            watermark::set(&pool, "order", ts).await?;
+        }
+        if let Some(ts) = max_item_ts {
+            watermark::set(&pool, "item", ts).await?;
+        }
+    }
 
     println!("{}", now.elapsed().as_millis());
 
     Ok(())
 }
 
-fn sort_data_customers(mut customer: Customer, orders_map: &HashMap<i64, Vec<Order>>) -> Customer {
-    match orders_map.get(&customer.customer_id) {
-        Some(orders) => customer.orders = orders.clone(),
-        _ => (),
-    }
-    customer
+/// Logs a structured, greppable failure and exits with the error's process
+/// code. Used for `IndexerError` variants that make continuing pointless
+/// (an empty/partial dataset would otherwise look like a quiet success).
+fn abort(err: IndexerError) -> ! {
+    eprintln!("[{}] {} ({})", err.code(), err, err.severity());
+    std::process::exit(err.exit_code());
 }
 
-fn sort_data_orders(mut order: Order, items_map: &HashMap<i64, Vec<Item>>) -> Order {
-    match items_map.get(&order.order_id) {
-        Some(items) => order.items = items.clone(),
-        _ => (),
-    } ;
-    order
+/// Builds the embedding pipeline from `EMBEDDING_MODEL`/`EMBEDDING_ENDPOINT`,
+/// or `None` if embeddings aren't configured for this run.
+fn embedding_pipeline_from_env() -> Option<embedding::Pipeline> {
+    let model = env::var("EMBEDDING_MODEL").ok()?;
+    let endpoint = env::var("EMBEDDING_ENDPOINT").ok()?;
+    let chunk_size = env::var("EMBEDDING_CHUNK_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(256);
+
+    Some(embedding::Pipeline::new("description", model, endpoint, chunk_size))
 }
 
-async fn bulk_insert_into_el(client: &Elasticsearch, data: Vec<Customer>, size: usize) -> Result<()> {
-    let mut body: Vec<JsonBody<_>> = Vec::with_capacity(size);
+/// Embeds the `description` field of every customer, order, and item in
+/// `customers` in place.
+pub(crate) fn embed_customer_trees(pipeline: &embedding::Pipeline, customers: &mut Vec<Customer>) -> Result<()> {
+    let customer_texts: Vec<String> = customers.iter().map(|c| c.description.clone()).collect();
+    let customer_embeddings = pipeline.embed_all(&customer_texts)?;
+
+    let order_texts: Vec<String> = customers.iter().flat_map(|c| c.orders.iter().map(|o| o.description.clone())).collect();
+    let order_embeddings = pipeline.embed_all(&order_texts)?;
+
+    let item_texts: Vec<String> = customers
+        .iter()
+        .flat_map(|c| c.orders.iter().flat_map(|o| o.items.iter().map(|i| i.description.clone())))
+        .collect();
+    let item_embeddings = pipeline.embed_all(&item_texts)?;
+
+    let mut order_iter = order_embeddings.into_iter();
+    let mut item_iter = item_embeddings.into_iter();
 
-    for (idx, customer) in data.iter().enumerate() {
-        body.push(json!({"index": {"_id": idx}}).into());
-        body.push(JsonBody::from(json!(customer)))
+    for (customer, embedding) in customers.iter_mut().zip(customer_embeddings) {
+        customer.description_embedding = embedding;
+
+        for order in &mut customer.orders {
+            order.description_embedding = order_iter.next().flatten();
+
+            for item in &mut order.items {
+                item.description_embedding = item_iter.next().flatten();
+            }
+        }
     }
 
-    let response = client
-        .bulk(BulkParts::Index("customer"))
-        .body(body)
+    Ok(())
+}
+
+/// Creates/updates the `customer` index mapping so `description_embedding`
+/// is a `dense_vector` field usable in `knn` queries.
+async fn ensure_customer_mapping(client: &Elasticsearch, pipeline: &embedding::Pipeline) -> Result<()> {
+    let _ = &pipeline.field;
+
+    client
+        .indices()
+        .put_mapping(elasticsearch::indices::IndicesPutMappingParts::Index(&["customer"]))
+        .body(json!({
+            "properties": {
+                "description_embedding": {
+                    "type": "dense_vector",
+                    "index": true,
+                    "similarity": "cosine"
+                },
+                "orders": {
+                    "properties": {
+                        "description_embedding": {
+                            "type": "dense_vector",
+                            "index": true,
+                            "similarity": "cosine"
+                        },
+                        "items": {
+                            "properties": {
+                                "description_embedding": {
+                                    "type": "dense_vector",
+                                    "index": true,
+                                    "similarity": "cosine"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }))
         .send()
         .await?;
 
-    let response_body = response.json::<Value>().await?;
-    let successful = !response_body["errors"].as_bool().unwrap();
-
-    println!("{}", successful);
-
     Ok(())
 }
 
-async fn fetch_all_customers(pool: &PgPool) -> Result<Vec<Customer>> {
+const CUSTOMER_COLUMNS: &[(&str, query::ColumnType)] = &[
+    ("customer_id", query::ColumnType::BigInt),
+    ("description", query::ColumnType::Text),
+    ("updated_at", query::ColumnType::Timestamptz),
+];
+const ORDER_COLUMNS: &[(&str, query::ColumnType)] = &[
+    ("order_id", query::ColumnType::BigInt),
+    ("order_description", query::ColumnType::Text),
+    ("customer_id", query::ColumnType::BigInt),
+    ("updated_at", query::ColumnType::Timestamptz),
+];
+const ITEM_COLUMNS: &[(&str, query::ColumnType)] = &[
+    ("item_id", query::ColumnType::BigInt),
+    ("item_description", query::ColumnType::Text),
+    ("order_id", query::ColumnType::BigInt),
+    ("updated_at", query::ColumnType::Timestamptz),
+];
+
+fn query_options(prefix: &str, allowed_columns: &[(&str, query::ColumnType)], table: &'static str) -> Result<query::QueryOptions, IndexerError> {
+    query::QueryOptions::from_env(prefix, allowed_columns)
+        .map_err(|e| IndexerError::FetchFailed { table, source: sqlx::Error::Configuration(e.into()) })
+}
+
+/// Fetches customers changed since the watermark (all of them if `since` is
+/// `None`), along with the latest `updated_at` seen so the caller can advance
+/// the watermark.
+async fn fetch_changed_customers(pool: &PgPool, since: Option<DateTime<Utc>>) -> Result<(Vec<Customer>, Option<DateTime<Utc>>), IndexerError> {
     let mut customers: Vec<Customer> = vec![];
+    let mut max_updated_at: Option<DateTime<Utc>> = None;
+
+    let options = query_options("CUSTOMER_QUERY", CUSTOMER_COLUMNS, "simple.customer")?;
+    let (fragment, filter_value) = options.fragment(2);
+    let default_order = if fragment.contains("ORDER BY") { "" } else { " ORDER BY customer_id" };
+    let sql = format!(
+        "SELECT customer_id, description, updated_at FROM simple.customer WHERE updated_at > COALESCE($1, '-infinity'::timestamptz){fragment}{default_order}"
+    );
+
+    let mut query = sqlx::query(&sql).bind(since);
+    if let Some(value) = filter_value {
+        query = query.bind(value);
+    }
 
-    let recs = sqlx::query!(
-        r#"
-SELECT customer_id, description
-FROM simple.customer
-ORDER BY customer_id
-        "#
-    )
+    let rows = query
         .fetch_all(pool)
-        .await?;
-
-    for rec in recs {
+        .await
+        .map_err(|source| IndexerError::FetchFailed { table: "simple.customer", source })?;
+
+    for row in rows {
+        let updated_at: DateTime<Utc> = row
+            .try_get("updated_at")
+            .map_err(|source| IndexerError::FetchFailed { table: "simple.customer", source })?;
+        max_updated_at = max_updated_at.max(Some(updated_at));
         customers.push(Customer {
-            customer_id: rec.customer_id,
-            description: rec.description.unwrap(),
+            customer_id: row
+                .try_get("customer_id")
+                .map_err(|source| IndexerError::FetchFailed { table: "simple.customer", source })?,
+            description: row
+                .try_get::<Option<String>, _>("description")
+                .map_err(|source| IndexerError::FetchFailed { table: "simple.customer", source })?
+                .unwrap_or_default(),
             orders: Vec::<Order>::new(),
-        }
-        )
+            description_embedding: None,
+        })
     }
 
-    Ok(customers)
+    Ok((customers, max_updated_at))
 }
 
-async fn fetch_all_orders(pool: &PgPool) -> Result<Vec<Order>> {
+async fn fetch_changed_orders(pool: &PgPool, since: Option<DateTime<Utc>>) -> Result<(Vec<Order>, Option<DateTime<Utc>>), IndexerError> {
     let mut orders: Vec<Order> = vec![];
+    let mut max_updated_at: Option<DateTime<Utc>> = None;
+
+    let options = query_options("ORDER_QUERY", ORDER_COLUMNS, "simple.order")?;
+    let (fragment, filter_value) = options.fragment(2);
+    let default_order = if fragment.contains("ORDER BY") { "" } else { " ORDER BY order_id" };
+    let sql = format!(
+        "SELECT order_id, order_description, customer_id, updated_at FROM simple.order WHERE updated_at > COALESCE($1, '-infinity'::timestamptz){fragment}{default_order}"
+    );
+
+    let mut query = sqlx::query(&sql).bind(since);
+    if let Some(value) = filter_value {
+        query = query.bind(value);
+    }
 
-    let recs = sqlx::query!(
-        r#"
-SELECT order_id, order_description, customer_id
-FROM simple.order
-ORDER BY order_id
-        "#
-    )
+    let rows = query
         .fetch_all(pool)
-        .await?;
-
-    for rec in recs {
+        .await
+        .map_err(|source| IndexerError::FetchFailed { table: "simple.order", source })?;
+
+    for row in rows {
+        let updated_at: DateTime<Utc> = row
+            .try_get("updated_at")
+            .map_err(|source| IndexerError::FetchFailed { table: "simple.order", source })?;
+        max_updated_at = max_updated_at.max(Some(updated_at));
         orders.push(Order {
-            order_id: rec.order_id,
-            description: rec.order_description.unwrap(),
-            customer_id: rec.customer_id.unwrap(),
+            order_id: row.try_get("order_id").map_err(|source| IndexerError::FetchFailed { table: "simple.order", source })?,
+            description: row
+                .try_get::<Option<String>, _>("order_description")
+                .map_err(|source| IndexerError::FetchFailed { table: "simple.order", source })?
+                .unwrap_or_default(),
+            customer_id: row
+                .try_get::<Option<i64>, _>("customer_id")
+                .map_err(|source| IndexerError::FetchFailed { table: "simple.order", source })?
+                .unwrap_or_default(),
             items: vec![],
-        }
-        )
+            description_embedding: None,
+        })
     }
 
-    Ok(orders)
+    Ok((orders, max_updated_at))
 }
 
-async fn fetch_all_items(pool: &PgPool) -> Result<Vec<Item>> {
+async fn fetch_changed_items(pool: &PgPool, since: Option<DateTime<Utc>>) -> Result<(Vec<Item>, Option<DateTime<Utc>>), IndexerError> {
     let mut items: Vec<Item> = vec![];
+    let mut max_updated_at: Option<DateTime<Utc>> = None;
+
+    let options = query_options("ITEM_QUERY", ITEM_COLUMNS, "simple.item")?;
+    let (fragment, filter_value) = options.fragment(2);
+    let default_order = if fragment.contains("ORDER BY") { "" } else { " ORDER BY item_id" };
+    let sql = format!(
+        "SELECT item_id, item_description, order_id, updated_at FROM simple.item WHERE updated_at > COALESCE($1, '-infinity'::timestamptz){fragment}{default_order}"
+    );
+
+    let mut query = sqlx::query(&sql).bind(since);
+    if let Some(value) = filter_value {
+        query = query.bind(value);
+    }
+
+    let rows = query
+        .fetch_all(pool)
+        .await
+        .map_err(|source| IndexerError::FetchFailed { table: "simple.item", source })?;
+
+    for row in rows {
+        let updated_at: DateTime<Utc> = row
+            .try_get("updated_at")
+            .map_err(|source| IndexerError::FetchFailed { table: "simple.item", source })?;
+        max_updated_at = max_updated_at.max(Some(updated_at));
+        items.push(Item {
+            item_id: row.try_get("item_id").map_err(|source| IndexerError::FetchFailed { table: "simple.item", source })?,
+            description: row
+                .try_get::<Option<String>, _>("item_description")
+                .map_err(|source| IndexerError::FetchFailed { table: "simple.item", source })?
+                .unwrap_or_default(),
+            order_id: row
+                .try_get::<Option<i64>, _>("order_id")
+                .map_err(|source| IndexerError::FetchFailed { table: "simple.item", source })?
+                .unwrap_or_default(),
+            description_embedding: None,
+        })
+    }
 
+    Ok((items, max_updated_at))
+}
+
+async fn customer_ids_for_order_ids(pool: &PgPool, order_ids: &[i64]) -> Result<Vec<i64>, IndexerError> {
     let recs = sqlx::query!(
         r#"
-SELECT item_id, item_description, order_id
-FROM simple.item
-ORDER BY item_id
-        "#
+SELECT DISTINCT customer_id
+FROM simple.order
+WHERE order_id = ANY($1)
+        "#,
+        order_ids,
     )
         .fetch_all(pool)
-        .await?;
+        .await
+        .map_err(|source| IndexerError::FetchFailed { table: "simple.order", source })?;
 
-    for rec in recs {
-        items.push(Item {
-            item_id: rec.item_id,
-            description: rec.item_description.unwrap(),
-            order_id: rec.order_id.unwrap(),
-        }
-        )
-    }
+    Ok(recs.into_iter().filter_map(|rec| rec.customer_id).collect())
+}
 
-    Ok(items)
-}
\ No newline at end of file
@@ -0,0 +1,366 @@
+use crate::embedding;
+use crate::error::IndexerError;
+use crate::sink::DocumentSink;
+use crate::{Customer, Item, Order};
+use crossbeam_channel::{bounded, Receiver};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use tokio::runtime::Handle;
+
+const CHANNEL_CAPACITY: usize = 1024;
+const INDEX_BATCH_SIZE: usize = 2000;
+
+struct CustomerRow {
+    customer_id: i64,
+    description: String,
+}
+
+struct OrderRow {
+    order_id: i64,
+    customer_id: i64,
+    description: String,
+}
+
+struct ItemRow {
+    item_id: i64,
+    order_id: i64,
+    customer_id: i64,
+    description: String,
+}
+
+/// Streams customers/orders/items for `customer_ids` through three concurrent
+/// DB-stream threads, a join stage, and an indexing stage connected by
+/// bounded channels, instead of materializing every row into `HashMap`s up
+/// front. Because all three queries are ordered by `customer_id`, the join
+/// stage only ever needs to hold the one customer currently being assembled.
+///
+/// Returns the `customer_id`s the sink rejected (a `BulkPartialFailure` is
+/// not treated as fatal); the caller is responsible for leaving those
+/// customers' jobs retriable rather than completing them.
+pub async fn run(
+    pool: &PgPool,
+    customer_ids: Vec<i64>,
+    sink: Arc<dyn DocumentSink>,
+    embedding_pipeline: Option<Arc<embedding::Pipeline>>,
+) -> Result<Vec<i64>, IndexerError> {
+    if customer_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let handle = Handle::current();
+    let (customer_tx, customer_rx) = bounded::<CustomerRow>(CHANNEL_CAPACITY);
+    let (order_tx, order_rx) = bounded::<OrderRow>(CHANNEL_CAPACITY);
+    let (item_tx, item_rx) = bounded::<ItemRow>(CHANNEL_CAPACITY);
+    let (joined_tx, joined_rx) = bounded::<Customer>(CHANNEL_CAPACITY);
+
+    // Each stream gets its own thread driving its own query, so the join
+    // stage can drain all three concurrently. Running them sequentially in
+    // one function would park the join stage's `peek_id` on whichever
+    // channel hasn't started producing yet, and once a query outgrows
+    // `CHANNEL_CAPACITY` its producer would block on a full, undrained
+    // channel forever.
+    let customers_pool = pool.clone();
+    let customers_ids = customer_ids.clone();
+    let customers_handle = handle.clone();
+    let customers_thread = thread::spawn(move || customers_handle.block_on(stream_customers(customers_pool, customers_ids, customer_tx)));
+
+    let orders_pool = pool.clone();
+    let orders_ids = customer_ids.clone();
+    let orders_handle = handle.clone();
+    let orders_thread = thread::spawn(move || orders_handle.block_on(stream_orders(orders_pool, orders_ids, order_tx)));
+
+    let items_pool = pool.clone();
+    let items_ids = customer_ids.clone();
+    let items_handle = handle.clone();
+    let items_thread = thread::spawn(move || items_handle.block_on(stream_items(items_pool, items_ids, item_tx)));
+
+    let join_thread = thread::spawn(move || join_stage(customer_rx, order_rx, item_rx, joined_tx));
+
+    let index_handle = handle.clone();
+    let index_result = index_handle
+        .spawn(index_stage(joined_rx, sink, embedding_pipeline))
+        .await
+        .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+
+    join_thread.join().expect("join stage thread panicked");
+    customers_thread
+        .join()
+        .expect("customer stream thread panicked")
+        .map_err(|source| IndexerError::FetchFailed { table: "simple.customer", source })?;
+    orders_thread
+        .join()
+        .expect("order stream thread panicked")
+        .map_err(|source| IndexerError::FetchFailed { table: "simple.order", source })?;
+    items_thread
+        .join()
+        .expect("item stream thread panicked")
+        .map_err(|source| IndexerError::FetchFailed { table: "simple.item", source })?;
+
+    index_result
+}
+
+async fn stream_customers(pool: PgPool, customer_ids: Vec<i64>, customer_tx: crossbeam_channel::Sender<CustomerRow>) -> Result<(), sqlx::Error> {
+    use futures::TryStreamExt;
+
+    let mut rows = sqlx::query!(
+        r#"
+SELECT customer_id, description
+FROM simple.customer
+WHERE customer_id = ANY($1)
+ORDER BY customer_id
+        "#,
+        &customer_ids,
+    )
+        .fetch(&pool);
+
+    while let Some(rec) = rows.try_next().await? {
+        let _ = customer_tx.send(CustomerRow {
+            customer_id: rec.customer_id,
+            description: rec.description.unwrap_or_default(),
+        });
+    }
+
+    Ok(())
+}
+
+async fn stream_orders(pool: PgPool, customer_ids: Vec<i64>, order_tx: crossbeam_channel::Sender<OrderRow>) -> Result<(), sqlx::Error> {
+    use futures::TryStreamExt;
+
+    let mut rows = sqlx::query!(
+        r#"
+SELECT order_id, order_description, customer_id
+FROM simple.order
+WHERE customer_id = ANY($1)
+ORDER BY customer_id, order_id
+        "#,
+        &customer_ids,
+    )
+        .fetch(&pool);
+
+    while let Some(rec) = rows.try_next().await? {
+        let _ = order_tx.send(OrderRow {
+            order_id: rec.order_id,
+            customer_id: rec.customer_id.unwrap_or_default(),
+            description: rec.order_description.unwrap_or_default(),
+        });
+    }
+
+    Ok(())
+}
+
+async fn stream_items(pool: PgPool, customer_ids: Vec<i64>, item_tx: crossbeam_channel::Sender<ItemRow>) -> Result<(), sqlx::Error> {
+    use futures::TryStreamExt;
+
+    let mut rows = sqlx::query!(
+        r#"
+SELECT i.item_id, i.item_description, i.order_id, o.customer_id
+FROM simple.item i
+JOIN simple.order o ON o.order_id = i.order_id
+WHERE o.customer_id = ANY($1)
+ORDER BY o.customer_id, i.order_id, i.item_id
+        "#,
+        &customer_ids,
+    )
+        .fetch(&pool);
+
+    while let Some(rec) = rows.try_next().await? {
+        let _ = item_tx.send(ItemRow {
+            item_id: rec.item_id,
+            order_id: rec.order_id,
+            customer_id: rec.customer_id.unwrap_or_default(),
+            description: rec.item_description.unwrap_or_default(),
+        });
+    }
+
+    Ok(())
+}
+
+/// A one-row lookahead buffer per input stream, so the join stage can peek
+/// at the next `customer_id` without consuming it.
+struct Lookahead<T> {
+    rx: Receiver<T>,
+    head: Option<T>,
+}
+
+impl<T> Lookahead<T> {
+    fn new(rx: Receiver<T>) -> Self {
+        Lookahead { rx, head: None }
+    }
+
+    fn peek_id(&mut self, id_of: impl Fn(&T) -> i64) -> Option<i64> {
+        if self.head.is_none() {
+            self.head = self.rx.recv().ok();
+        }
+        self.head.as_ref().map(&id_of)
+    }
+
+    fn take(&mut self) -> T {
+        self.head.take().expect("take called without a buffered head")
+    }
+}
+
+fn join_stage(
+    customer_rx: Receiver<CustomerRow>,
+    order_rx: Receiver<OrderRow>,
+    item_rx: Receiver<ItemRow>,
+    joined_tx: crossbeam_channel::Sender<Customer>,
+) {
+    let mut customers = Lookahead::new(customer_rx);
+    let mut orders = Lookahead::new(order_rx);
+    let mut items = Lookahead::new(item_rx);
+
+    loop {
+        let candidates = [
+            customers.peek_id(|r| r.customer_id),
+            orders.peek_id(|r| r.customer_id),
+            items.peek_id(|r| r.customer_id),
+        ];
+        let min_id = match candidates.iter().flatten().min().copied() {
+            Some(id) => id,
+            None => break,
+        };
+
+        let mut customer = None;
+        while customers.peek_id(|r| r.customer_id) == Some(min_id) {
+            let row = customers.take();
+            customer = Some(Customer {
+                customer_id: row.customer_id,
+                description: row.description,
+                orders: Vec::new(),
+                description_embedding: None,
+            });
+        }
+
+        let mut orders_by_id: HashMap<i64, Order> = HashMap::new();
+        while orders.peek_id(|r| r.customer_id) == Some(min_id) {
+            let row = orders.take();
+            orders_by_id.insert(
+                row.order_id,
+                Order {
+                    order_id: row.order_id,
+                    description: row.description,
+                    customer_id: row.customer_id,
+                    items: Vec::new(),
+                    description_embedding: None,
+                },
+            );
+        }
+
+        while items.peek_id(|r| r.customer_id) == Some(min_id) {
+            let row = items.take();
+            if let Some(order) = orders_by_id.get_mut(&row.order_id) {
+                order.items.push(Item {
+                    item_id: row.item_id,
+                    description: row.description,
+                    order_id: row.order_id,
+                    description_embedding: None,
+                });
+            }
+        }
+
+        if let Some(mut customer) = customer {
+            customer.orders = orders_by_id.into_values().collect();
+            if joined_tx.send(customer).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// How many `INDEX_BATCH_SIZE` writes (each driving its own embedding calls
+/// and sink round-trip) are allowed in flight at once, so a large backlog
+/// doesn't pile up unbounded concurrent work on top of tokio's blocking pool.
+const MAX_CONCURRENT_BATCHES: usize = 4;
+
+async fn index_stage(
+    joined_rx: Receiver<Customer>,
+    sink: Arc<dyn DocumentSink>,
+    embedding_pipeline: Option<Arc<embedding::Pipeline>>,
+) -> Result<Vec<i64>, IndexerError> {
+    let mut batch: Vec<Customer> = Vec::with_capacity(INDEX_BATCH_SIZE);
+    let mut in_flight = Vec::new();
+    let mut failed_customer_ids = Vec::new();
+
+    loop {
+        let next = {
+            let joined_rx = joined_rx.clone();
+            tokio::task::spawn_blocking(move || joined_rx.recv().ok())
+                .await
+                .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?
+        };
+
+        match next {
+            Some(customer) => {
+                batch.push(customer);
+                if batch.len() >= INDEX_BATCH_SIZE {
+                    if in_flight.len() >= MAX_CONCURRENT_BATCHES {
+                        collect_batch_result(in_flight.remove(0), &mut failed_customer_ids).await?;
+                    }
+                    in_flight.push(spawn_write(std::mem::take(&mut batch), sink.clone(), embedding_pipeline.clone()));
+                }
+            }
+            None => {
+                if !batch.is_empty() {
+                    if in_flight.len() >= MAX_CONCURRENT_BATCHES {
+                        collect_batch_result(in_flight.remove(0), &mut failed_customer_ids).await?;
+                    }
+                    in_flight.push(spawn_write(std::mem::take(&mut batch), sink.clone(), embedding_pipeline.clone()));
+                }
+                break;
+            }
+        }
+    }
+
+    for handle in in_flight {
+        collect_batch_result(handle, &mut failed_customer_ids).await?;
+    }
+
+    Ok(failed_customer_ids)
+}
+
+/// Awaits one batch write, folding a `BulkPartialFailure` into
+/// `failed_customer_ids` rather than aborting the whole run over it. Any
+/// other error is treated as fatal and propagated.
+async fn collect_batch_result(
+    handle: tokio::task::JoinHandle<Result<(), IndexerError>>,
+    failed_customer_ids: &mut Vec<i64>,
+) -> Result<(), IndexerError> {
+    match handle.await.map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))? {
+        Ok(()) => Ok(()),
+        Err(IndexerError::BulkPartialFailure { failed_ids }) => {
+            failed_customer_ids.extend(failed_ids.iter().filter_map(|id| id.parse().ok()));
+            Ok(())
+        }
+        Err(fatal) => Err(fatal),
+    }
+}
+
+fn spawn_write(
+    mut batch: Vec<Customer>,
+    sink: Arc<dyn DocumentSink>,
+    embedding_pipeline: Option<Arc<embedding::Pipeline>>,
+) -> tokio::task::JoinHandle<Result<(), IndexerError>> {
+    tokio::spawn(async move {
+        if let Some(pipeline) = embedding_pipeline.clone() {
+            // `embed_customer_trees` makes blocking `reqwest::blocking` calls;
+            // run it on tokio's blocking pool so it doesn't stall the worker
+            // thread other batches and the index stage share.
+            batch = tokio::task::spawn_blocking(move || {
+                crate::embed_customer_trees(&pipeline, &mut batch)?;
+                Ok::<_, anyhow::Error>(batch)
+            })
+            .await
+            .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?
+            .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+        }
+
+        let report = sink.write_batch(&batch).await?;
+        if !report.failed_ids.is_empty() {
+            return Err(IndexerError::BulkPartialFailure { failed_ids: report.failed_ids });
+        }
+
+        Ok(())
+    })
+}
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Result};
+use std::env;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+
+    fn from_str(s: &str) -> Result<SortDirection> {
+        match s.to_uppercase().as_str() {
+            "ASC" => Ok(SortDirection::Asc),
+            "DESC" => Ok(SortDirection::Desc),
+            other => Err(anyhow!("unknown sort direction: {other}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FilterOp {
+    Eq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+impl FilterOp {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            FilterOp::Eq => "=",
+            FilterOp::Gt => ">",
+            FilterOp::Gte => ">=",
+            FilterOp::Lt => "<",
+            FilterOp::Lte => "<=",
+        }
+    }
+
+    fn from_str(s: &str) -> Result<FilterOp> {
+        match s {
+            "eq" => Ok(FilterOp::Eq),
+            "gt" => Ok(FilterOp::Gt),
+            "gte" => Ok(FilterOp::Gte),
+            "lt" => Ok(FilterOp::Lt),
+            "lte" => Ok(FilterOp::Lte),
+            other => Err(anyhow!("unknown filter op: {other}")),
+        }
+    }
+}
+
+/// The Postgres type an allow-listed column holds, so a filter value (always
+/// read out of the environment as a string) can be cast to something the
+/// column's own operators accept instead of being bound as `TEXT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Text,
+    BigInt,
+    Timestamptz,
+}
+
+impl ColumnType {
+    fn cast(&self) -> &'static str {
+        match self {
+            ColumnType::Text => "text",
+            ColumnType::BigInt => "bigint",
+            ColumnType::Timestamptz => "timestamptz",
+        }
+    }
+}
+
+/// A user-supplied sort/filter for one `fetch_changed_*` query, analogous
+/// to a `with_sorting(order)` builder option elsewhere. Columns are
+/// validated against an allow-list so config values can't be used to inject
+/// arbitrary SQL into the fragment we assemble.
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    sort: Option<(String, SortDirection)>,
+    filter: Option<(String, ColumnType, FilterOp, String)>,
+}
+
+impl QueryOptions {
+    /// Reads `{prefix}_SORT_COLUMN`/`{prefix}_SORT_DIRECTION` and
+    /// `{prefix}_FILTER_COLUMN`/`{prefix}_FILTER_OP`/`{prefix}_FILTER_VALUE`
+    /// from the environment, validating every column against
+    /// `allowed_columns`. Any option left unset is simply omitted.
+    pub fn from_env(prefix: &str, allowed_columns: &[(&str, ColumnType)]) -> Result<QueryOptions> {
+        let mut options = QueryOptions::default();
+
+        if let Ok(column) = env::var(format!("{prefix}_SORT_COLUMN")) {
+            validate_column(&column, allowed_columns)?;
+            let direction = env::var(format!("{prefix}_SORT_DIRECTION"))
+                .ok()
+                .map(|d| SortDirection::from_str(&d))
+                .transpose()?
+                .unwrap_or(SortDirection::Asc);
+            options.sort = Some((column, direction));
+        }
+
+        if let Ok(column) = env::var(format!("{prefix}_FILTER_COLUMN")) {
+            let column_type = validate_column(&column, allowed_columns)?;
+            let op = env::var(format!("{prefix}_FILTER_OP"))
+                .ok()
+                .map(|op| FilterOp::from_str(&op))
+                .transpose()?
+                .unwrap_or(FilterOp::Eq);
+            let value = env::var(format!("{prefix}_FILTER_VALUE"))
+                .map_err(|_| anyhow!("{prefix}_FILTER_COLUMN set without {prefix}_FILTER_VALUE"))?;
+            options.filter = Some((column, column_type, op, value));
+        }
+
+        Ok(options)
+    }
+
+    /// An `AND ... ORDER BY ...` (or `ORDER BY ...` / empty) fragment to
+    /// append after the query's own fixed `WHERE` clause, plus the filter
+    /// value to bind as the next placeholder (`$N`) if present. The
+    /// placeholder is cast to the filter column's own type (`$N::bigint`,
+    /// `$N::timestamptz`, ...) since the value is always bound as text.
+    pub fn fragment(&self, next_placeholder: usize) -> (String, Option<&str>) {
+        let mut fragment = String::new();
+        let mut bind_value = None;
+
+        if let Some((column, column_type, op, value)) = &self.filter {
+            fragment.push_str(&format!(
+                " AND {column} {} ${next_placeholder}::{}",
+                op.as_sql(),
+                column_type.cast()
+            ));
+            bind_value = Some(value.as_str());
+        }
+
+        if let Some((column, direction)) = &self.sort {
+            fragment.push_str(&format!(" ORDER BY {column} {}", direction.as_sql()));
+        }
+
+        (fragment, bind_value)
+    }
+}
+
+fn validate_column(column: &str, allowed_columns: &[(&str, ColumnType)]) -> Result<ColumnType> {
+    allowed_columns
+        .iter()
+        .find(|(name, _)| *name == column)
+        .map(|(_, column_type)| *column_type)
+        .ok_or_else(|| anyhow!("column '{column}' is not in the allow-list for this query"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALLOWED: &[(&str, ColumnType)] = &[("customer_id", ColumnType::BigInt), ("description", ColumnType::Text)];
+
+    #[test]
+    fn validate_column_accepts_allow_listed_columns() {
+        assert_eq!(validate_column("customer_id", ALLOWED).unwrap(), ColumnType::BigInt);
+    }
+
+    #[test]
+    fn validate_column_rejects_everything_else() {
+        assert!(validate_column("drop table customer_id; --", ALLOWED).is_err());
+    }
+
+    #[test]
+    fn fragment_casts_the_placeholder_to_the_filter_column_type() {
+        let options = QueryOptions {
+            sort: None,
+            filter: Some(("customer_id".to_string(), ColumnType::BigInt, FilterOp::Gt, "100".to_string())),
+        };
+
+        let (fragment, value) = options.fragment(2);
+
+        assert_eq!(fragment, " AND customer_id > $2::bigint");
+        assert_eq!(value, Some("100"));
+    }
+
+    #[test]
+    fn fragment_combines_filter_and_sort() {
+        let options = QueryOptions {
+            sort: Some(("customer_id".to_string(), SortDirection::Desc)),
+            filter: Some(("description".to_string(), ColumnType::Text, FilterOp::Eq, "vip".to_string())),
+        };
+
+        let (fragment, value) = options.fragment(2);
+
+        assert_eq!(fragment, " AND description = $2::text ORDER BY customer_id DESC");
+        assert_eq!(value, Some("vip"));
+    }
+
+    #[test]
+    fn fragment_is_empty_with_no_sort_or_filter() {
+        let options = QueryOptions::default();
+        let (fragment, value) = options.fragment(2);
+
+        assert_eq!(fragment, "");
+        assert_eq!(value, None);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::compression;
+use crate::error::IndexerError;
+use crate::Customer;
+use async_trait::async_trait;
+use elasticsearch::http::headers::{HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE};
+use elasticsearch::http::Method;
+use elasticsearch::Elasticsearch;
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Outcome of writing one batch to a sink: how many documents were attempted
+/// and which ids (if any) the sink rejected.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub attempted: usize,
+    pub failed_ids: Vec<String>,
+}
+
+/// An output destination for indexed `Customer` trees. Lets the indexer ship
+/// to Elasticsearch, MeiliSearch, or a plain object-store dump without the
+/// pipeline caring which.
+#[async_trait]
+pub trait DocumentSink: Send + Sync {
+    async fn write_batch(&self, docs: &[Customer]) -> Result<BatchReport, IndexerError>;
+}
+
+/// Whether `SINK_URL` (default `es://customer`) selects the Elasticsearch
+/// backend, so callers can gate Elasticsearch-only setup (pinging the
+/// cluster, `ensure_customer_mapping`) without constructing a sink first.
+pub fn is_elasticsearch_sink() -> bool {
+    env::var("SINK_URL").map(|url| url.starts_with("es://")).unwrap_or(true)
+}
+
+/// Builds the configured sink from `SINK_URL` (default `es://customer`).
+/// The scheme selects the backend: `es://<index>`, `meili://<host>/<uid>`,
+/// `s3://<bucket>/<prefix>`, `file://<dir>`, or `memory://<namespace>`.
+pub fn from_config(client: Elasticsearch) -> Result<Box<dyn DocumentSink>, IndexerError> {
+    let url = env::var("SINK_URL").unwrap_or_else(|_| "es://customer".to_string());
+
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| IndexerError::ElasticsearchUnreachable(format!("invalid SINK_URL: {url}")))?;
+
+    match scheme {
+        "es" => Ok(Box::new(ElasticsearchSink {
+            client,
+            index: rest.to_string(),
+        })),
+        "meili" => {
+            let (base_url, index_uid) = rest
+                .split_once('/')
+                .ok_or_else(|| IndexerError::ElasticsearchUnreachable(format!("SINK_URL missing index uid: {url}")))?;
+            Ok(Box::new(MeiliSearchSink {
+                base_url: format!("http://{base_url}"),
+                index_uid: index_uid.to_string(),
+                client: reqwest::Client::new(),
+            }))
+        }
+        "s3" => {
+            let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+            let store = AmazonS3Builder::from_env()
+                .with_bucket_name(bucket)
+                .build()
+                .map_err(|e| IndexerError::ElasticsearchUnreachable(format!("failed to configure s3 sink: {e}")))?;
+            Ok(Box::new(ObjectStoreSink {
+                backend: ObjectStoreBackend::S3 {
+                    store: Arc::new(store),
+                    prefix: prefix.to_string(),
+                },
+            }))
+        }
+        "file" => Ok(Box::new(ObjectStoreSink {
+            backend: ObjectStoreBackend::Filesystem(PathBuf::from(rest)),
+        })),
+        "memory" => Ok(Box::new(ObjectStoreSink {
+            backend: ObjectStoreBackend::Memory(Arc::new(Mutex::new(Vec::new()))),
+        })),
+        other => Err(IndexerError::ElasticsearchUnreachable(format!("unsupported sink scheme: {other}"))),
+    }
+}
+
+/// The original Elasticsearch bulk sink: NDJSON body, compressed, shipped via
+/// `_bulk`, with per-item failures parsed out of the response.
+pub struct ElasticsearchSink {
+    client: Elasticsearch,
+    index: String,
+}
+
+#[async_trait]
+impl DocumentSink for ElasticsearchSink {
+    async fn write_batch(&self, docs: &[Customer]) -> Result<BatchReport, IndexerError> {
+        let mut lines: Vec<Vec<u8>> = Vec::with_capacity(docs.len() * 2);
+
+        for customer in docs {
+            lines.push(
+                serde_json::to_vec(&json!({"index": {"_id": customer.customer_id.to_string()}})).expect("json! value always serializes"),
+            );
+            lines.push(serde_json::to_vec(&json!(customer)).expect("Customer always serializes"));
+        }
+
+        let compression = compression::CompressionConfig::from_env()
+            .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+        let newline: &[u8] = b"\n";
+        let chunks = lines.iter().flat_map(|line| [line.as_slice(), newline]);
+        let compressed_body = compression::compress_stream(compression, chunks)
+            .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson"));
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(compression.codec.content_encoding()));
+
+        let response = self
+            .client
+            .transport()
+            .send(
+                Method::Post,
+                &format!("/{}/_bulk", self.index),
+                headers,
+                Option::<&()>::None,
+                Some(compressed_body),
+                None,
+            )
+            .await
+            .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+
+        let response_body = response
+            .json::<Value>()
+            .await
+            .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+
+        Ok(BatchReport {
+            attempted: docs.len(),
+            failed_ids: failed_item_ids(&response_body),
+        })
+    }
+}
+
+/// Parses the bulk response's `items` array (rather than just the top-level
+/// `errors` bool) to find which documents failed.
+fn failed_item_ids(response_body: &Value) -> Vec<String> {
+    response_body["items"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| {
+            let action = item.get("index").or_else(|| item.get("create")).or_else(|| item.get("update"))?;
+            if action.get("error").is_some() {
+                Some(action["_id"].as_str().unwrap_or("unknown").to_string())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// POSTs documents to a MeiliSearch-style `/indexes/{uid}/documents` endpoint.
+pub struct MeiliSearchSink {
+    base_url: String,
+    index_uid: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl DocumentSink for MeiliSearchSink {
+    async fn write_batch(&self, docs: &[Customer]) -> Result<BatchReport, IndexerError> {
+        let response = self
+            .client
+            .post(format!("{}/indexes/{}/documents", self.base_url, self.index_uid))
+            .json(docs)
+            .send()
+            .await
+            .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(IndexerError::ElasticsearchUnreachable(format!(
+                "meilisearch returned {}",
+                response.status()
+            )));
+        }
+
+        Ok(BatchReport {
+            attempted: docs.len(),
+            failed_ids: Vec::new(),
+        })
+    }
+}
+
+/// Where an `ObjectStoreSink` writes its compressed NDJSON shards. `Memory`
+/// actually retains shards (rather than discarding them) so a dry run can be
+/// inspected, instead of silently reporting success over a no-op.
+enum ObjectStoreBackend {
+    Filesystem(PathBuf),
+    S3 { store: Arc<dyn ObjectStore>, prefix: String },
+    Memory(Arc<Mutex<Vec<(String, Vec<u8>)>>>),
+}
+
+/// Writes each batch as an NDJSON shard compressed with the bulk compression
+/// codec (gzip by default), named after the first `customer_id` in the batch,
+/// to a configurable backend selected by URL scheme.
+pub struct ObjectStoreSink {
+    backend: ObjectStoreBackend,
+}
+
+#[async_trait]
+impl DocumentSink for ObjectStoreSink {
+    async fn write_batch(&self, docs: &[Customer]) -> Result<BatchReport, IndexerError> {
+        let mut ndjson = Vec::new();
+        for doc in docs {
+            ndjson.extend(serde_json::to_vec(&json!(doc)).expect("Customer always serializes"));
+            ndjson.push(b'\n');
+        }
+
+        let compression = compression::CompressionConfig::from_env()
+            .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+        let shard = compression::compress_stream(compression, std::iter::once(ndjson.as_slice()))
+            .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+        let shard_name = format!("customer-{}.ndjson.{}", uuid_like_suffix(docs), compression.codec.content_encoding());
+
+        match &self.backend {
+            ObjectStoreBackend::Filesystem(dir) => {
+                fs::create_dir_all(dir).map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+                let mut file = fs::File::create(dir.join(&shard_name)).map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+                file.write_all(&shard).map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+            }
+            ObjectStoreBackend::S3 { store, prefix } => {
+                let path = ObjectPath::from(format!("{prefix}{shard_name}"));
+                store
+                    .put(&path, shard.clone().into())
+                    .await
+                    .map_err(|e| IndexerError::ElasticsearchUnreachable(e.to_string()))?;
+            }
+            ObjectStoreBackend::Memory(shards) => {
+                shards.lock().expect("memory sink mutex poisoned").push((shard_name.clone(), shard.clone()));
+            }
+        }
+
+        Ok(BatchReport {
+            attempted: docs.len(),
+            failed_ids: Vec::new(),
+        })
+    }
+}
+
+fn uuid_like_suffix(docs: &[Customer]) -> String {
+    docs.first().map(|c| c.customer_id.to_string()).unwrap_or_else(|| "empty".to_string())
+}
@@ -0,0 +1,34 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+/// Reads the `last_indexed` watermark for `entity`, or `None` if this entity
+/// has never been indexed (callers should treat that as "fetch everything").
+pub async fn get(pool: &PgPool, entity: &str) -> Result<Option<DateTime<Utc>>> {
+    let rec = sqlx::query!(
+        r#"
+SELECT last_indexed FROM indexer_watermark WHERE entity = $1
+        "#,
+        entity,
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(rec.map(|r| r.last_indexed))
+}
+
+pub async fn set(pool: &PgPool, entity: &str, last_indexed: DateTime<Utc>) -> Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO indexer_watermark (entity, last_indexed)
+VALUES ($1, $2)
+ON CONFLICT (entity) DO UPDATE SET last_indexed = EXCLUDED.last_indexed
+        "#,
+        entity,
+        last_indexed,
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}